@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Result of running a command through a `Backend`
+#[derive(Debug, Clone)]
+pub struct ExecResult {
+    pub success: bool,
+    pub exit_code: Option<i32>,
+}
+
+/// Where a job's steps are actually executed. `LocalBackend` runs on this
+/// machine; `SshBackend`/`DockerBackend` ship the rendered command to a
+/// remote host or container, letting a single config orchestrate steps
+/// across local, remote and containerized targets.
+pub trait Backend {
+    fn run(
+        &self,
+        cmd_body: &str,
+        env: &HashMap<String, String>,
+        cwd: Option<&str>,
+        stdio: Stdio,
+        timeout: Option<Duration>,
+        shell: &[String],
+    ) -> io::Result<ExecResult>;
+
+    /// Run `cmd_body` the same way as `run`, but capture its stdout/stderr
+    /// instead of sending them to a `Stdio`, for callers (`Step::Assert`,
+    /// `Step::Register`) that need to inspect the output rather than just
+    /// forward it. Goes through the same target (local/ssh/docker) as `run`,
+    /// so these step kinds honor a job's `backend` like every other step.
+    fn run_captured(
+        &self,
+        cmd_body: &str,
+        env: &HashMap<String, String>,
+        cwd: Option<&str>,
+        timeout: Option<Duration>,
+        shell: &[String],
+    ) -> io::Result<(ExecResult, String, String)>;
+}
+
+/// Build the `Command` that will execute `cmd_body`. A body starting with a
+/// `#!` shebang line is written to an executable temp file and run directly,
+/// the same way `just` handles multi-line script steps, so Python/Ruby/etc.
+/// bodies don't need shell-quoting escapes. Otherwise `cmd_body` is passed as
+/// the final argument to `shell` (e.g. `["/bin/sh", "-c"]`). Returns the temp
+/// script path alongside the `Command`, if one was created, so the caller can
+/// remove it once the step has finished.
+pub(crate) fn command_for_body(cmd_body: &str, shell: &[String]) -> io::Result<(Command, Option<PathBuf>)> {
+    if cmd_body.starts_with("#!") {
+        let script_path = write_shebang_script(cmd_body)?;
+        Ok((Command::new(&script_path), Some(script_path)))
+    } else {
+        let (program, args) = shell
+            .split_first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "shell must not be empty"))?;
+        let mut cmd = Command::new(program);
+        cmd.args(args);
+        cmd.arg(cmd_body);
+        Ok((cmd, None))
+    }
+}
+
+/// Write a shebang script body to a fresh temp file and mark it executable
+fn write_shebang_script(body: &str) -> io::Result<PathBuf> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let id = COUNTER.fetch_add(1, Ordering::SeqCst);
+
+    let mut path = std::env::temp_dir();
+    path.push(format!("monaco-script-{}-{id}", std::process::id()));
+    std::fs::write(&path, body)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = std::fs::metadata(&path)?.permissions();
+        perms.set_mode(0o755);
+        std::fs::set_permissions(&path, perms)?;
+    }
+
+    Ok(path)
+}
+
+/// Spawn `cmd_ctx` with piped stdout/stderr, enforcing `timeout` the same way
+/// `wait_with_timeout` does for a `Backend`, and return its exit status
+/// alongside the captured streams. Reader threads drain the pipes
+/// concurrently with the wait so a chatty child can't deadlock blocking on a
+/// full pipe buffer.
+pub(crate) fn spawn_and_capture(
+    mut cmd_ctx: Command,
+    timeout: Option<Duration>,
+) -> io::Result<(ExitStatus, String, String)> {
+    cmd_ctx.stdout(Stdio::piped());
+    cmd_ctx.stderr(Stdio::piped());
+    let mut child = cmd_ctx.spawn()?;
+
+    let mut stdout_pipe = child.stdout.take().expect("child spawned with piped stdout");
+    let mut stderr_pipe = child.stderr.take().expect("child spawned with piped stderr");
+    let stdout_reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stdout_pipe.read_to_end(&mut buf);
+        buf
+    });
+    let stderr_reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stderr_pipe.read_to_end(&mut buf);
+        buf
+    });
+
+    let status = wait_with_timeout(child, timeout)?;
+    let stdout = stdout_reader.join().unwrap_or_default();
+    let stderr = stderr_reader.join().unwrap_or_default();
+
+    Ok((
+        status,
+        String::from_utf8_lossy(&stdout).to_string(),
+        String::from_utf8_lossy(&stderr).to_string(),
+    ))
+}
+
+/// Wait for `child` to exit, killing it if `timeout` elapses first. A
+/// monitor thread owns the deadline so the caller can still block on the
+/// normal `wait()`; the `done` flag keeps it from killing a child that
+/// already exited on its own.
+fn wait_with_timeout(mut child: Child, timeout: Option<Duration>) -> io::Result<ExitStatus> {
+    let Some(timeout) = timeout else {
+        return child.wait();
+    };
+
+    let pid = child.id();
+    let done = Arc::new(AtomicBool::new(false));
+    let monitor_done = done.clone();
+    let monitor = std::thread::spawn(move || {
+        std::thread::sleep(timeout);
+        if !monitor_done.load(Ordering::SeqCst) {
+            kill_pid(pid);
+        }
+    });
+
+    let status = child.wait();
+    done.store(true, Ordering::SeqCst);
+    // Not joined: the monitor sleeps out the whole timeout, and joining it
+    // would hold every step until the deadline even after the child exited
+    drop(monitor);
+    status
+}
+
+/// Kill a process by pid, used to enforce a step's `timeout_secs`
+fn kill_pid(pid: u32) {
+    if cfg!(windows) {
+        let _ = Command::new("taskkill")
+            .args(["/PID", &pid.to_string(), "/F"])
+            .status();
+    } else {
+        let _ = Command::new("kill")
+            .args(["-9", &pid.to_string()])
+            .status();
+    }
+}
+
+/// Run commands on the local machine through the system shell
+pub struct LocalBackend;
+
+impl Backend for LocalBackend {
+    fn run(
+        &self,
+        cmd_body: &str,
+        env: &HashMap<String, String>,
+        cwd: Option<&str>,
+        stdio: Stdio,
+        timeout: Option<Duration>,
+        shell: &[String],
+    ) -> io::Result<ExecResult> {
+        let (mut cmd_ctx, script_path) = command_for_body(cmd_body, shell)?;
+
+        cmd_ctx.envs(env);
+        if let Some(dir) = cwd {
+            cmd_ctx.current_dir(dir);
+        }
+        cmd_ctx.stdout(stdio);
+
+        let status = wait_with_timeout(cmd_ctx.spawn()?, timeout)?;
+        if let Some(script_path) = script_path {
+            let _ = std::fs::remove_file(script_path);
+        }
+        Ok(ExecResult {
+            success: status.success(),
+            exit_code: status.code(),
+        })
+    }
+
+    fn run_captured(
+        &self,
+        cmd_body: &str,
+        env: &HashMap<String, String>,
+        cwd: Option<&str>,
+        timeout: Option<Duration>,
+        shell: &[String],
+    ) -> io::Result<(ExecResult, String, String)> {
+        let (mut cmd_ctx, script_path) = command_for_body(cmd_body, shell)?;
+
+        cmd_ctx.envs(env);
+        if let Some(dir) = cwd {
+            cmd_ctx.current_dir(dir);
+        }
+
+        let (status, stdout, stderr) = spawn_and_capture(cmd_ctx, timeout)?;
+        if let Some(script_path) = script_path {
+            let _ = std::fs::remove_file(script_path);
+        }
+        Ok((
+            ExecResult {
+                success: status.success(),
+                exit_code: status.code(),
+            },
+            stdout,
+            stderr,
+        ))
+    }
+}
+
+/// Run commands on a remote host over `ssh user@host -- <cmd>`
+pub struct SshBackend {
+    pub host: String,
+}
+
+impl Backend for SshBackend {
+    fn run(
+        &self,
+        cmd_body: &str,
+        env: &HashMap<String, String>,
+        cwd: Option<&str>,
+        stdio: Stdio,
+        timeout: Option<Duration>,
+        // `shell`/shebang steps are a local concern; the remote command
+        // already runs through the remote host's own default shell
+        _shell: &[String],
+    ) -> io::Result<ExecResult> {
+        let remote_cmd = remote_command(cmd_body, env, cwd);
+        let mut cmd_ctx = Command::new("ssh");
+        cmd_ctx.args([self.host.as_str(), "--", &remote_cmd]);
+        cmd_ctx.stdout(stdio);
+
+        let status = wait_with_timeout(cmd_ctx.spawn()?, timeout)?;
+        Ok(ExecResult {
+            success: status.success(),
+            exit_code: status.code(),
+        })
+    }
+
+    fn run_captured(
+        &self,
+        cmd_body: &str,
+        env: &HashMap<String, String>,
+        cwd: Option<&str>,
+        timeout: Option<Duration>,
+        // `shell`/shebang steps are a local concern; the remote command
+        // already runs through the remote host's own default shell
+        _shell: &[String],
+    ) -> io::Result<(ExecResult, String, String)> {
+        let remote_cmd = remote_command(cmd_body, env, cwd);
+        let mut cmd_ctx = Command::new("ssh");
+        cmd_ctx.args([self.host.as_str(), "--", &remote_cmd]);
+
+        let (status, stdout, stderr) = spawn_and_capture(cmd_ctx, timeout)?;
+        Ok((
+            ExecResult {
+                success: status.success(),
+                exit_code: status.code(),
+            },
+            stdout,
+            stderr,
+        ))
+    }
+}
+
+/// Run commands inside a running container via `docker exec <container> sh -c <cmd>`
+pub struct DockerBackend {
+    pub container: String,
+}
+
+impl Backend for DockerBackend {
+    fn run(
+        &self,
+        cmd_body: &str,
+        env: &HashMap<String, String>,
+        cwd: Option<&str>,
+        stdio: Stdio,
+        timeout: Option<Duration>,
+        // `shell`/shebang steps are a local concern; `docker exec` already
+        // runs the command through the container's own `sh -c`
+        _shell: &[String],
+    ) -> io::Result<ExecResult> {
+        let remote_cmd = remote_command(cmd_body, env, cwd);
+        let mut cmd_ctx = Command::new("docker");
+        cmd_ctx.args(["exec", self.container.as_str(), "sh", "-c", &remote_cmd]);
+        cmd_ctx.stdout(stdio);
+
+        let status = wait_with_timeout(cmd_ctx.spawn()?, timeout)?;
+        Ok(ExecResult {
+            success: status.success(),
+            exit_code: status.code(),
+        })
+    }
+
+    fn run_captured(
+        &self,
+        cmd_body: &str,
+        env: &HashMap<String, String>,
+        cwd: Option<&str>,
+        timeout: Option<Duration>,
+        // `shell`/shebang steps are a local concern; `docker exec` already
+        // runs the command through the container's own `sh -c`
+        _shell: &[String],
+    ) -> io::Result<(ExecResult, String, String)> {
+        let remote_cmd = remote_command(cmd_body, env, cwd);
+        let mut cmd_ctx = Command::new("docker");
+        cmd_ctx.args(["exec", self.container.as_str(), "sh", "-c", &remote_cmd]);
+
+        let (status, stdout, stderr) = spawn_and_capture(cmd_ctx, timeout)?;
+        Ok((
+            ExecResult {
+                success: status.success(),
+                exit_code: status.code(),
+            },
+            stdout,
+            stderr,
+        ))
+    }
+}
+
+/// Fold `env`/`cwd` into a single shell command string, for backends that
+/// only get to ship one command across a transport (ssh, docker exec)
+fn remote_command(cmd_body: &str, env: &HashMap<String, String>, cwd: Option<&str>) -> String {
+    let mut parts = Vec::new();
+    for (key, value) in env.iter() {
+        parts.push(format!("export {key}={}", shell_quote(value)));
+    }
+    if let Some(dir) = cwd {
+        parts.push(format!("cd {}", shell_quote(dir)));
+    }
+    parts.push(cmd_body.to_string());
+    parts.join(" && ")
+}
+
+/// Single-quote `value` for a POSIX shell, escaping embedded `'` as `'\''`.
+/// Unlike Rust's `{:?}` Debug quoting, this is safe against `$()`/backtick
+/// command substitution on the remote end.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Which `Backend` a job's steps run through
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BackendSpec {
+    Local,
+    Ssh { host: String },
+    Docker { container: String },
+}
+
+impl Default for BackendSpec {
+    fn default() -> Self {
+        BackendSpec::Local
+    }
+}
+
+impl BackendSpec {
+    pub fn build(&self) -> Box<dyn Backend + Send + Sync> {
+        match self {
+            BackendSpec::Local => Box::new(LocalBackend),
+            BackendSpec::Ssh { host } => Box::new(SshBackend { host: host.clone() }),
+            BackendSpec::Docker { container } => Box::new(DockerBackend {
+                container: container.clone(),
+            }),
+        }
+    }
+}
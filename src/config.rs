@@ -29,6 +29,20 @@ pub struct Config {
     /// Where to write the step output
     pub log: Option<StepLog>,
 
+    /// Default interpreter used to run every job's steps, as a program plus
+    /// its argument vector (e.g. `["/usr/bin/env", "python3", "-c"]`).
+    /// Defaults to `["/bin/sh", "-c"]` (`["cmd", "/C"]` on Windows). A job's
+    /// own `shell` overrides this.
+    pub shell: Option<Vec<String>>,
+
+    /// Additional config files to merge in, as glob patterns resolved
+    /// relative to this file's directory. `env`/`props` are deep-merged with
+    /// later includes overriding earlier ones and the root config winning
+    /// last; jobs are concatenated and duplicate job names across files are
+    /// rejected.
+    #[serde(default)]
+    pub include: Vec<String>,
+
     /// Jobs to execute
     #[serde(default)]
     // jobs: HashMap<String, Job>,
@@ -36,19 +50,88 @@ pub struct Config {
 }
 
 impl Config {
-    /// Create a configuration from a file
+    /// Create a configuration from a file, merging in any `include` globs
     pub fn from_file(path: &str) -> Result<Self, Box<dyn Error>> {
         // let config_path = match fs::canonicalize(&path)
         match File::open(path) {
             Ok(fp) => {
                 let hint = Path::new(path).extension().unwrap();
                 let mut reader = BufReader::new(fp);
-                return Config::from_reader(&mut reader, hint.to_str().unwrap());
+                let mut config = Config::from_reader(&mut reader, hint.to_str().unwrap())?;
+                let config_dir = Path::new(path).parent().unwrap_or_else(|| Path::new("."));
+                config.merge_includes(config_dir)?;
+                Ok(config)
             }
             Err(e) => return Err(Box::new(e)),
         }
     }
 
+    /// Expand `include` globs (relative to `config_dir`) and merge their
+    /// `env`/`props`/`jobs` into this config. Later includes override
+    /// earlier ones, and this config (the root) wins last. Job names that
+    /// collide across files are rejected, naming the offending file.
+    fn merge_includes(&mut self, config_dir: &Path) -> Result<(), Box<dyn Error>> {
+        if self.include.is_empty() {
+            return Ok(());
+        }
+
+        let mut merged_env = HashMap::new();
+        let mut merged_props = HashMap::new();
+        let mut merged_jobs: Vec<Job> = Vec::new();
+        let mut owner: HashMap<String, String> = HashMap::new();
+
+        for pattern in &self.include {
+            let full_pattern = config_dir.join(pattern);
+            let full_pattern = full_pattern.to_str().ok_or("include pattern is not valid UTF-8")?;
+
+            for entry in glob::glob(full_pattern)? {
+                let file_path = entry?;
+                let hint = file_path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or_default();
+                let mut reader = BufReader::new(File::open(&file_path)?);
+                let included = Config::from_reader(&mut reader, hint)?;
+
+                merged_env.extend(included.env);
+                merged_props.extend(included.props);
+
+                for job in included.jobs {
+                    if let Some(existing) = owner.get(&job.name) {
+                        return Err(format!(
+                            "Duplicate job '{}' in '{}' (already defined in '{}')",
+                            job.name,
+                            file_path.display(),
+                            existing
+                        )
+                        .into());
+                    }
+                    owner.insert(job.name.clone(), file_path.display().to_string());
+                    merged_jobs.push(job);
+                }
+            }
+        }
+
+        for job in &self.jobs {
+            if let Some(existing) = owner.get(&job.name) {
+                return Err(format!(
+                    "Duplicate job '{}' in the root config (already defined in '{}')",
+                    job.name, existing
+                )
+                .into());
+            }
+        }
+
+        merged_env.extend(self.env.drain());
+        merged_props.extend(self.props.drain());
+        merged_jobs.append(&mut self.jobs);
+
+        self.env = merged_env;
+        self.props = merged_props;
+        self.jobs = merged_jobs;
+        Ok(())
+    }
+
     /// Create a configuration from a reader
     pub fn from_reader<R: std::io::Read>(
         reader: &mut R,
@@ -123,7 +206,14 @@ impl Config {
             //         }
             //     }
             // }
-            job.completed.set(false);
+            job.completed.store(false, std::sync::atomic::Ordering::SeqCst);
+            job.failed.store(false, std::sync::atomic::Ordering::SeqCst);
+            job.ran.store(false, std::sync::atomic::Ordering::SeqCst);
+        }
+
+        if let Err(cycle) = topo_order(&self.jobs) {
+            println!("Dependency cycle detected among jobs => {:?}", cycle);
+            return false;
         }
 
         return true;
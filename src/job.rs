@@ -1,19 +1,119 @@
 use serde::{Deserialize, Serialize};
-use std::cell::Cell;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Bounds of a `Iter::Range`. `deny_unknown_fields` keeps it from swallowing
+/// `Iter::Matrix` axis maps, which don't share its `from`/`to`/`by` shape.
+/// Signed so a range can descend (`from` > `to`, negative `by`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RangeSpec {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub by: Option<i64>,
+}
 
 /// Iterations for the steps
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Iter {
     /// A range of values. By default starts at 0
-    Range {
-        from: Option<u64>,
-        to: Option<u64>,
-        by: Option<u64>,
-    },
+    Range(RangeSpec),
     /// List of values
     Values(Vec<serde_yaml::Value>),
+    /// Elements loaded from a JSON array file, honoring the job's `dir`
+    File(String),
+    /// Repeat the job's steps until one fails. `Loop(false)` does not repeat:
+    /// the steps run once, the same as with no `iters`
+    Loop(bool),
+    /// Named axes iterated as their Cartesian product, e.g.
+    /// `{ region: [...], size: {from, to, by} }`
+    Matrix(HashMap<String, Iter>),
+}
+
+/// Compute the values of the range `[from, to)` stepping by `by`,
+/// which may be negative for a descending range
+fn range_values(from: i64, to: i64, by: i64) -> Vec<serde_json::Value> {
+    if by == 0 {
+        return Vec::new();
+    }
+    let mut values = Vec::new();
+    let mut current = from;
+    if by > 0 {
+        while current < to {
+            values.push(serde_json::json!(current));
+            current += by;
+        }
+    } else {
+        while current > to {
+            values.push(serde_json::json!(current));
+            current += by;
+        }
+    }
+    values
+}
+
+/// Resolve a single iteration axis into its concrete sequence of values.
+/// A `Matrix` recurses into each of its named axes and combines them.
+fn resolve_iter(iter: &Iter, dir: Option<&str>) -> Vec<serde_json::Value> {
+    match iter {
+        Iter::Range(RangeSpec { from, to, by }) => {
+            let from = from.unwrap_or(0);
+            let to = to.unwrap_or(0);
+            let by = by.unwrap_or(1);
+            range_values(from, to, by)
+        }
+        Iter::Values(values) => values
+            .iter()
+            .map(|v| serde_json::to_value(v).unwrap_or(serde_json::Value::Null))
+            .collect(),
+        Iter::File(path) => {
+            let file_path = match dir {
+                Some(dir) => std::path::Path::new(dir).join(path),
+                None => std::path::PathBuf::from(path),
+            };
+            match deserialize_file::<Vec<serde_json::Value>>(&file_path) {
+                Ok(values) => values,
+                Err(e) => {
+                    println!(
+                        "Could not load iteration file '{}' => {e}",
+                        file_path.display()
+                    );
+                    Vec::new()
+                }
+            }
+        }
+        // Driven by the caller instead of materialized up front
+        Iter::Loop(_) => Vec::new(),
+        Iter::Matrix(axes) => {
+            let mut names: Vec<&String> = axes.keys().collect();
+            names.sort();
+
+            let mut product: Vec<serde_json::Map<String, serde_json::Value>> =
+                vec![serde_json::Map::new()];
+            for name in names {
+                let values = resolve_iter(&axes[name], dir);
+                let mut next = Vec::with_capacity(product.len() * values.len());
+                for combo in &product {
+                    for value in &values {
+                        let mut combo = combo.clone();
+                        combo.insert(name.clone(), value.clone());
+                        next.push(combo);
+                    }
+                }
+                product = next;
+            }
+            product.into_iter().map(serde_json::Value::Object).collect()
+        }
+    }
+}
+
+/// Deserialize a JSON file into `T`
+pub fn deserialize_file<T: serde::de::DeserializeOwned>(
+    path: &std::path::Path,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
 }
 
 /// Where to log the output of
@@ -34,8 +134,28 @@ impl Default for StepLog {
     }
 }
 
+/// A single assertion rule for a captured stream.
+///
+/// `exit` rules compare against the process exit code, `stdout`/`stderr`
+/// rules are either a regex matched anywhere in the captured stream, or an
+/// exact string the stream content must equal in full.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AssertRule {
+    /// Exact exit code to expect
+    Exit(i32),
+    /// Regex the stream content must match
+    Pattern(String),
+    /// Exact byte sequence the stream content must equal
+    Exact { exact: String },
+}
+
+/// Map from stream name (`stdout`, `stderr`, `exit`) to its assertion rule
+pub type Assertions = HashMap<String, AssertRule>;
+
 /// Steps that a job can execute. It can execute:
 /// - A shell command
+/// - A shell command with expected output assertions
 /// - Another job
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -43,6 +163,13 @@ pub enum Step {
     /// The command is directly a string template
     Command(String),
 
+    /// A command with per-stream output assertions, used in `--test` mode
+    Assert { cmd: String, assert: Assertions },
+
+    /// A command whose trimmed stdout is bound to `register` in `props`,
+    /// so later steps and `when` conditions can reference `{{ props.<name> }}`
+    Register { cmd: String, register: String },
+
     /// The command is another job
     #[serde(rename(deserialize = "job"))]
     Job {
@@ -52,7 +179,7 @@ pub enum Step {
 }
 
 /// Jobs are executed in the order they are on the config file
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Job {
     /// Descriptive name of the Job
     pub name: String,
@@ -81,6 +208,9 @@ pub struct Job {
     /// List of conditions to check if the job should be executed
     pub when: Option<Vec<String>>,
 
+    /// Default assertions applied to every plain `Step::Command` in `--test` mode
+    pub assert: Option<Assertions>,
+
     // Templates to executeP
     pub templates: Option<Vec<String>>,
 
@@ -91,7 +221,144 @@ pub struct Job {
     // Output of the job
     pub log: Option<StepLog>,
 
-    // Whether the job was completed
-    #[serde(default)]
-    pub completed: Cell<bool>,
+    /// Where this job's steps are executed. Defaults to the local machine
+    pub backend: Option<crate::backend::BackendSpec>,
+
+    /// Interpreter used to run this job's steps, as a program plus its
+    /// argument vector (e.g. `["/usr/bin/env", "python3", "-c"]`), overriding
+    /// the global `Config::shell`. Ignored for a step whose body starts with
+    /// a `#!` shebang line, which is run directly instead.
+    pub shell: Option<Vec<String>>,
+
+    /// Number of extra attempts after a step fails. Defaults to 0 (no retry)
+    pub retries: Option<u32>,
+
+    /// Base delay before a retry, doubled after each failed attempt
+    /// (`retry_delay_ms * 2^attempt`). Defaults to 0
+    pub retry_delay_ms: Option<u64>,
+
+    /// Kill a step's process if it runs longer than this many seconds
+    pub timeout_secs: Option<u64>,
+
+    /// Extra glob patterns (relative to the config file's directory) to
+    /// watch for this job in `--watch` mode, beyond its `dir` and template inputs
+    pub watch: Option<Vec<String>>,
+
+    /// Whether the job was completed. An `AtomicBool` so the DAG scheduler
+    /// can share `&Job` across worker threads and gate dependents safely.
+    #[serde(skip, default)]
+    pub completed: AtomicBool,
+
+    /// Whether the job failed (and `ignore_errors` was not set). Checked by
+    /// the scheduler to skip transitive dependents instead of running them.
+    #[serde(skip, default)]
+    pub failed: AtomicBool,
+
+    /// Whether the job actually executed its steps this run, as opposed to
+    /// being skipped by the incremental cache. Checked by dependents so an
+    /// upstream job that ran invalidates their own cache hit.
+    #[serde(skip, default)]
+    pub ran: AtomicBool,
+}
+
+impl Clone for Job {
+    fn clone(&self) -> Self {
+        Job {
+            name: self.name.clone(),
+            dir: self.dir.clone(),
+            env: self.env.clone(),
+            props: self.props.clone(),
+            secrets: self.secrets.clone(),
+            steps: self.steps.clone(),
+            iters: self.iters.clone(),
+            depends: self.depends.clone(),
+            when: self.when.clone(),
+            assert: self.assert.clone(),
+            templates: self.templates.clone(),
+            ignore_errors: self.ignore_errors,
+            log: self.log.clone(),
+            backend: self.backend.clone(),
+            shell: self.shell.clone(),
+            retries: self.retries,
+            retry_delay_ms: self.retry_delay_ms,
+            timeout_secs: self.timeout_secs,
+            watch: self.watch.clone(),
+            completed: AtomicBool::new(self.completed.load(Ordering::SeqCst)),
+            failed: AtomicBool::new(self.failed.load(Ordering::SeqCst)),
+            ran: AtomicBool::new(self.ran.load(Ordering::SeqCst)),
+        }
+    }
+}
+
+impl Job {
+    /// Resolve this job's `iters` into the concrete sequence of per-iteration
+    /// template values. `Loop(true)` is driven by the caller rather than
+    /// materialized here, so it resolves to an empty vector; use
+    /// `Job::is_looping` to detect it.
+    pub fn resolve_iters(&self) -> Vec<serde_json::Value> {
+        match &self.iters {
+            None => Vec::new(),
+            Some(iters) => resolve_iter(iters, self.dir.as_deref()),
+        }
+    }
+
+    /// Whether `iters` asks for a caller-driven repeat-until-failure loop
+    pub fn is_looping(&self) -> bool {
+        matches!(&self.iters, Some(Iter::Loop(true)))
+    }
+}
+
+/// Compute a topological order over `jobs` based on the `Step::Job` edges in
+/// each job's `depends`. Returns the ordered job names, or the set of job
+/// names that could not be ordered because the dependency graph has a cycle.
+pub fn topo_order(jobs: &[Job]) -> Result<Vec<String>, Vec<String>> {
+    let names: Vec<&str> = jobs.iter().map(|j| j.name.as_str()).collect();
+    let mut in_degree: HashMap<&str, usize> = names.iter().map(|&n| (n, 0)).collect();
+    let mut dependents: HashMap<&str, Vec<&str>> = names.iter().map(|&n| (n, Vec::new())).collect();
+
+    for job in jobs.iter() {
+        if let Some(depends) = &job.depends {
+            for depend in depends.iter() {
+                if let Step::Job { job: dep_name, .. } = depend {
+                    if let Some(degree) = in_degree.get_mut(job.name.as_str()) {
+                        *degree += 1;
+                    }
+                    dependents
+                        .entry(dep_name.as_str())
+                        .or_default()
+                        .push(job.name.as_str());
+                }
+            }
+        }
+    }
+
+    let mut queue: VecDeque<&str> = in_degree
+        .iter()
+        .filter(|(_, &degree)| degree == 0)
+        .map(|(&name, _)| name)
+        .collect();
+    let mut order = Vec::with_capacity(names.len());
+
+    while let Some(name) = queue.pop_front() {
+        order.push(name.to_string());
+        for &dependent in dependents.get(name).into_iter().flatten() {
+            if let Some(degree) = in_degree.get_mut(dependent) {
+                *degree -= 1;
+                if *degree == 0 {
+                    queue.push_back(dependent);
+                }
+            }
+        }
+    }
+
+    if order.len() == names.len() {
+        Ok(order)
+    } else {
+        let remaining = in_degree
+            .into_iter()
+            .filter(|(_, degree)| *degree > 0)
+            .map(|(name, _)| name.to_string())
+            .collect();
+        Err(remaining)
+    }
 }
@@ -1,9 +1,12 @@
 use clap::Parser;
 use std::process::exit;
 
+mod backend;
 mod config;
 mod job;
+mod report;
 mod runner;
+mod state;
 
 use crate::config::*;
 use crate::runner::*;
@@ -23,6 +26,26 @@ struct Args {
     /// Run in dry mode (Don't execute steps, just print them)
     #[arg(long, default_value_t = false)]
     dry: bool,
+
+    /// Run every job's steps in assertion mode and report pass/fail/skip
+    #[arg(long, default_value_t = false)]
+    test: bool,
+
+    /// Ignore the persisted incremental state and re-run every job
+    #[arg(long, default_value_t = false)]
+    force: bool,
+
+    /// Stay resident and re-run affected jobs when watched files change
+    #[arg(long, default_value_t = false)]
+    watch: bool,
+
+    /// Maximum number of jobs to run concurrently (0 = unbounded)
+    #[arg(long, default_value_t = 0)]
+    jobs: usize,
+
+    /// Write a JSON report of every job's result to this path
+    #[arg(long)]
+    report: Option<String>,
 }
 
 fn main() -> Result<(), ()> {
@@ -48,11 +71,29 @@ fn main() -> Result<(), ()> {
 
     let mut runner = Runner::new(&config, &config_dir);
     runner.dry_mode = args.dry;
+    runner.force = args.force;
+    runner.max_parallel = args.jobs;
+
+    if args.test {
+        let summary = runner.run_all_tests();
+        println!(
+            "Tests: {} passed, {} failed, {} skipped",
+            summary.passed, summary.failed, summary.skipped
+        );
+        exit(if summary.failed > 0 { 1 } else { 0 });
+    }
+
+    if args.watch {
+        runner.run_watch(config_path.to_str().unwrap());
+        return Ok(());
+    }
 
     if let Some(jobname) = args.job {
         match runner.get_job(&jobname) {
             Some(job) => {
                 let sucess = runner.run_job(job);
+                runner.save_state();
+                write_report(&runner, &args.report);
                 exit(if sucess { 0 } else { 1 });
             }
             None => {
@@ -65,5 +106,17 @@ fn main() -> Result<(), ()> {
     };
 
     runner.run_all();
+    runner.save_state();
+    write_report(&runner, &args.report);
     Ok(())
 }
+
+/// Serialize the runner's collected `JobResult`s to `path`, if given
+fn write_report(runner: &Runner, path: &Option<String>) {
+    let Some(path) = path else {
+        return;
+    };
+    if let Err(e) = report::write_report(path, &runner.results()) {
+        println!("Could not write report to '{}' => {e}", path);
+    }
+}
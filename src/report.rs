@@ -0,0 +1,38 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Lifecycle of a single job run, pending -> running -> succeeded/failed/skipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JobState {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+/// Outcome of running (or skipping) a single job, collected by the `Runner`
+/// so `--report` can emit machine-readable results instead of stdout.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobResult {
+    pub name: String,
+    pub state: JobState,
+    /// Derived from the last step's success, not the process's real exit
+    /// code, since `Runner::run_step` only reports success as a `bool`
+    pub exit_code: Option<i32>,
+    pub started_at_ms: u128,
+    pub duration_ms: u128,
+    /// Captured output of the failing step when it is a `Step::Assert`.
+    /// A plain `Step::Command` streams straight to its `StepLog` destination
+    /// instead of being captured, and a failing `Step::Register` keeps no
+    /// output, so this is `None` for those, and for every job that did not fail.
+    pub output_tail: Option<String>,
+}
+
+/// Write the collected `results` as JSON to `path`
+pub fn write_report(path: &str, results: &[JobResult]) -> std::io::Result<()> {
+    let content = serde_json::to_string_pretty(results)?;
+    fs::write(Path::new(path), content)
+}
@@ -1,10 +1,74 @@
 use crate::job::*;
-use std::collections::HashMap;
-use std::process::{Command, Stdio};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
 
 use handlebars::Handlebars;
+use notify::{RecursiveMode, Watcher};
+use regex::Regex;
 
 use crate::config::{read_dotenv, Config};
+use crate::report::{JobResult, JobState};
+use crate::state::{fingerprint_job, State};
+
+/// Outcome of checking a step's actual output against its `Assertions`
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssertOutcome {
+    Passed,
+    Failed(Vec<String>),
+}
+
+/// Check captured `stdout`/`stderr`/`exit_code` against `assertions`,
+/// returning a failure message per unmet or invalid rule (empty when every
+/// rule passes)
+fn check_assertions(assertions: &Assertions, stdout: &str, stderr: &str, exit_code: i32) -> Vec<String> {
+    let mut failures = Vec::new();
+    for (stream, rule) in assertions.iter() {
+        let matched = match (stream.as_str(), rule) {
+            ("exit", AssertRule::Exit(expected)) => exit_code == *expected,
+            ("stdout", AssertRule::Pattern(pattern)) => match Regex::new(pattern) {
+                Ok(re) => re.is_match(stdout),
+                Err(e) => {
+                    failures.push(format!("stdout: invalid pattern '{pattern}' => {e}"));
+                    continue;
+                }
+            },
+            ("stderr", AssertRule::Pattern(pattern)) => match Regex::new(pattern) {
+                Ok(re) => re.is_match(stderr),
+                Err(e) => {
+                    failures.push(format!("stderr: invalid pattern '{pattern}' => {e}"));
+                    continue;
+                }
+            },
+            ("stdout", AssertRule::Exact { exact }) => stdout == exact,
+            ("stderr", AssertRule::Exact { exact }) => stderr == exact,
+            (stream, rule) => {
+                failures.push(format!("unsupported assertion '{stream}': {rule:?}"));
+                continue;
+            }
+        };
+
+        if !matched {
+            let actual = match stream.as_str() {
+                "exit" => exit_code.to_string(),
+                "stdout" => stdout.to_string(),
+                "stderr" => stderr.to_string(),
+                _ => unreachable!(),
+            };
+            failures.push(format!("{stream}: expected {rule:?}, got {actual:?}"));
+        }
+    }
+    failures
+}
+
+/// Summary of a `--test` run, reported once all jobs have been checked
+#[derive(Debug, Clone, Default)]
+pub struct TestSummary {
+    pub passed: u32,
+    pub failed: u32,
+    pub skipped: u32,
+}
 
 /// Resolve a log output
 /// Given the global output and the job output, resolve the one to use
@@ -16,6 +80,42 @@ pub fn resolve_log(global: &StepLog, local: &Option<StepLog>) -> StepLog {
     }
 }
 
+/// Resolve the interpreter a job's steps run through
+/// Given the global shell and the job's own override, resolve the one to use
+pub fn resolve_shell(global: &[String], local: &Option<Vec<String>>) -> Vec<String> {
+    match local {
+        Some(shell) => shell.clone(),
+        None => global.to_vec(),
+    }
+}
+
+/// Default interpreter: `/bin/sh -c` on Unix, `cmd /C` on Windows
+fn default_shell() -> Vec<String> {
+    if cfg!(windows) {
+        vec!["cmd".to_string(), "/C".to_string()]
+    } else {
+        vec!["/bin/sh".to_string(), "-c".to_string()]
+    }
+}
+
+/// Max length of a `JobResult::output_tail`, in characters
+const MAX_OUTPUT_TAIL_CHARS: usize = 4000;
+
+/// Keep only the last `MAX_OUTPUT_TAIL_CHARS` characters of `output`
+fn truncate_tail(output: &str) -> String {
+    if output.chars().count() <= MAX_OUTPUT_TAIL_CHARS {
+        return output.to_string();
+    }
+    output
+        .chars()
+        .rev()
+        .take(MAX_OUTPUT_TAIL_CHARS)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect()
+}
+
 /// The Runner is in charge of running all jobs
 #[derive(Debug)]
 pub struct Runner<'a> {
@@ -36,6 +136,25 @@ pub struct Runner<'a> {
 
     /// Global log output
     pub log: StepLog,
+
+    /// Global interpreter used to run steps, overridden per-job by `Job::shell`
+    pub shell: Vec<String>,
+
+    /// Directory the config file lives in, used to resolve relative paths
+    pub config_dir: String,
+
+    /// Skip the persisted incremental state and re-run every job
+    pub force: bool,
+
+    /// Maximum number of jobs to run concurrently within a dependency level.
+    /// `0` means unbounded (run every ready job at once).
+    pub max_parallel: usize,
+
+    /// Job fingerprints from the previous run, used to skip unchanged jobs
+    state: std::sync::Mutex<State>,
+
+    /// Results collected from every job run, in completion order
+    results: std::sync::Mutex<Vec<JobResult>>,
 }
 
 impl<'a> Runner<'static> {
@@ -57,6 +176,8 @@ impl<'a> Runner<'static> {
             None => StepLog::ToStdout(true),
         };
 
+        let shell = config.shell.clone().unwrap_or_else(default_shell);
+
         Runner {
             jobs: config.jobs.clone(),
             env,
@@ -64,9 +185,53 @@ impl<'a> Runner<'static> {
             dry_mode: false,
             ctx: Handlebars::new(),
             log,
+            shell,
+            state: std::sync::Mutex::new(State::load(config_dir)),
+            results: std::sync::Mutex::new(Vec::new()),
+            config_dir: config_dir.to_string(),
+            force: false,
+            max_parallel: 0,
         }
     }
 
+    /// Persist the incremental state (job fingerprints) next to the config
+    pub fn save_state(&self) {
+        self.state.lock().unwrap().save(&self.config_dir);
+    }
+
+    /// Results collected from every job run so far, in completion order
+    pub fn results(&self) -> Vec<JobResult> {
+        self.results.lock().unwrap().clone()
+    }
+
+    /// Record a job's outcome for `--report`, computing its duration from
+    /// `started_at`. `output_tail` is the last captured step output (if any),
+    /// truncated to `MAX_OUTPUT_TAIL_CHARS` so CI users have something to
+    /// look at on a `Failed` job without dumping the entire run.
+    fn record_result(
+        &self,
+        name: &str,
+        state: JobState,
+        exit_code: Option<i32>,
+        started_at: std::time::SystemTime,
+        output_tail: Option<String>,
+    ) {
+        let started_at_ms = started_at
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let duration_ms = started_at.elapsed().map(|d| d.as_millis()).unwrap_or(0);
+
+        self.results.lock().unwrap().push(JobResult {
+            name: name.to_string(),
+            state,
+            exit_code,
+            started_at_ms,
+            duration_ms,
+            output_tail: output_tail.as_deref().map(truncate_tail),
+        });
+    }
+
     /// Get a list of all available job names
     pub fn get_job_names(&self) -> Vec<&str> {
         self.jobs
@@ -75,86 +240,750 @@ impl<'a> Runner<'static> {
             .collect::<Vec<_>>()
     }
 
-    /// Resolve a path
-    /// A path is treated as a template
-    pub fn resolve_path(&'a self, path: &'a str) -> &str {
-        return path;
+    /// Resolve a path template (e.g. a `StepLog::Filepath` or a template's
+    /// output path) against the current job/props/iteration context
+    pub fn resolve_path(
+        &self,
+        path: &str,
+        job: &Job,
+        props: &HashMap<String, serde_yaml::Value>,
+        iter: &serde_json::Value,
+        index: usize,
+    ) -> String {
+        self.ctx
+            .render_template(path, &self.render_context(job, props, iter, index))
+            .unwrap()
     }
 
-    /// Interpreter used to execute commands
-    /// Defaults to `/bin/sh -c` on linux
-    /// Defaults to `cmd /C` on windows
-    /// Returns true if the command executed properly and false otherwise
-    pub fn run_step(&self, cmd: &str, job: &Job) -> bool {
-        let mut cmd_ctx: Command;
+    /// Render every `input:output` template pair of `job` through the same
+    /// Handlebars context used for commands (job name, props, dir, the
+    /// current `iter`/`index`), writing each rendered file to its resolved
+    /// output path. `output` is itself resolved as a path template (e.g.
+    /// `config.{{iter}}.yaml`), and its parent directories are created as
+    /// needed. Runs before the job's steps so generated files exist when
+    /// commands execute. In `dry_mode`, only prints what would be rendered
+    /// and never touches disk, the same as `run_step`.
+    fn render_templates(
+        &self,
+        job: &Job,
+        props: &HashMap<String, serde_yaml::Value>,
+        iter: &serde_json::Value,
+        index: usize,
+    ) {
+        let Some(templates) = &job.templates else {
+            return;
+        };
 
-        // let data: HashMap<String, Any> = HashMap::new();
+        for template in templates.iter() {
+            let Some((input, output)) = template.split_once(":") else {
+                continue;
+            };
 
-        // TODO: Implement the proper template data
-        let cmd_body = self
-            .ctx
-            .render_template(
-                &cmd,
-                &serde_json::json!({"job": job.name, "props": job.props, "iter": (), "dir": job.dir}),
-            )
-            .unwrap();
+            println!("Template => {}", template);
+            if self.dry_mode {
+                continue;
+            }
 
-        // TODO: Add option to change shell
-        if cfg!(windows) {
-            cmd_ctx = Command::new("cmd");
-            cmd_ctx.args(vec!["/C", &cmd_body]);
-        } else {
-            cmd_ctx = Command::new("/bin/sh");
-            cmd_ctx.args(vec!["-c", &cmd_body]);
+            let input_path = Path::new(&self.config_dir).join(input);
+            let content = match std::fs::read_to_string(&input_path) {
+                Ok(content) => content,
+                Err(e) => {
+                    println!("Could not read template '{}' => {e}", input_path.display());
+                    continue;
+                }
+            };
+
+            let rendered = match self
+                .ctx
+                .render_template(&content, &self.render_context(job, props, iter, index))
+            {
+                Ok(rendered) => rendered,
+                Err(e) => {
+                    println!("Could not render template '{}' => {e}", input_path.display());
+                    continue;
+                }
+            };
+
+            let output_path = self.resolve_path(output, job, props, iter, index);
+            let output_path = Path::new(&self.config_dir).join(output_path);
+            if let Some(parent) = output_path.parent() {
+                if let Err(e) = std::fs::create_dir_all(parent) {
+                    println!("Could not create directory '{}' => {e}", parent.display());
+                    continue;
+                }
+            }
+
+            if let Err(e) = std::fs::write(&output_path, rendered) {
+                println!("Could not write template output '{}' => {e}", output_path.display());
+            }
         }
+    }
 
-        let output = resolve_log(&self.log, &job.log);
-        match output {
+    /// Resolve the `Stdio` a step's output should go to, combining the
+    /// runner's global `log` setting with the job's own override
+    fn step_stdio(
+        &self,
+        job: &Job,
+        props: &HashMap<String, serde_yaml::Value>,
+        iter: &serde_json::Value,
+        index: usize,
+    ) -> Stdio {
+        match resolve_log(&self.log, &job.log) {
             StepLog::ToStdout(is_out) => {
-                if !is_out {
-                    cmd_ctx.stdout(Stdio::null());
+                if is_out {
+                    Stdio::inherit()
+                } else {
+                    Stdio::null()
                 }
             }
             StepLog::Filepath(out_template) => {
-                let out_path = self.resolve_path(&out_template);
+                let out_path = self.resolve_path(&out_template, job, props, iter, index);
                 let out_file = std::fs::OpenOptions::new()
                     .append(true)
                     .create(true)
                     .open(out_path)
                     .expect("Could not open log file");
-                cmd_ctx.stdout(out_file);
+                Stdio::from(out_file)
             }
         }
+    }
+
+    /// Template context shared by every step-rendering entry point: the
+    /// job's name/props/dir, plus the current iteration's `iter` value and
+    /// ordinal `index` (`Value::Null`/`0` outside of `job.iters`)
+    fn render_context(
+        &self,
+        job: &Job,
+        props: &HashMap<String, serde_yaml::Value>,
+        iter: &serde_json::Value,
+        index: usize,
+    ) -> serde_json::Value {
+        serde_json::json!({"job": job.name, "props": props, "iter": iter, "index": index, "dir": job.dir})
+    }
 
-        cmd_ctx.envs(&self.env);
+    /// Interpreter used to execute commands. Resolved from `Job::shell`,
+    /// falling back to the global `Config::shell` (`/bin/sh -c` on Unix,
+    /// `cmd /C` on Windows). Ignored for a step whose body starts with a
+    /// `#!` shebang line, which is run directly instead.
+    /// Returns true if the command executed properly and false otherwise.
+    /// Retries up to `job.retries` times with exponential backoff
+    /// (`retry_delay_ms * 2^attempt`), and kills the step if it runs past
+    /// `job.timeout_secs`, before giving up and returning false.
+    pub fn run_step(
+        &self,
+        cmd: &str,
+        job: &Job,
+        props: &HashMap<String, serde_yaml::Value>,
+        iter: &serde_json::Value,
+        index: usize,
+    ) -> bool {
+        let cmd_body = self
+            .ctx
+            .render_template(&cmd, &self.render_context(job, props, iter, index))
+            .unwrap();
+
+        let mut env = self.env.clone();
         if let Some(job_env) = &job.env {
-            cmd_ctx.envs(job_env);
+            env.extend(job_env.clone());
         }
 
-        if let Some(root) = &job.dir {
-            cmd_ctx.current_dir(&root);
+        println!("Step => {}", cmd);
+        if self.dry_mode {
+            return true;
         }
 
+        let backend = job.backend.clone().unwrap_or_default().build();
+        let shell = resolve_shell(&self.shell, &job.shell);
+        let timeout = job.timeout_secs.map(Duration::from_secs);
+        let retries = job.retries.unwrap_or(0);
+        let retry_delay_ms = job.retry_delay_ms.unwrap_or(0);
+
+        for attempt in 0..=retries {
+            let stdio = self.step_stdio(job, props, iter, index);
+            let success = matches!(
+                backend.run(&cmd_body, &env, job.dir.as_deref(), stdio, timeout, &shell),
+                Ok(result) if result.success
+            );
+
+            if success {
+                return true;
+            }
+
+            if attempt < retries {
+                let backoff_ms = retry_delay_ms.saturating_mul(1u64 << attempt);
+                println!(
+                    "Step failed (attempt {}/{}), retrying in {backoff_ms}ms => {}",
+                    attempt + 1,
+                    retries + 1,
+                    cmd
+                );
+                std::thread::sleep(Duration::from_millis(backoff_ms));
+            }
+        }
+
+        false
+    }
+
+    /// Run a step and return its trimmed stdout, for `Step::Register` to bind
+    /// into `props`. Runs through the job's configured `backend`, same as
+    /// `run_step`, so a `Step::Register` on a `ssh`/`docker` job still runs
+    /// remotely. Retries up to `job.retries` times with exponential backoff
+    /// and kills the step if it runs past `job.timeout_secs`, the same as
+    /// `run_step`. Returns `None` if every attempt failed to spawn or exited
+    /// non-zero.
+    fn run_step_capture(
+        &self,
+        cmd: &str,
+        job: &Job,
+        props: &HashMap<String, serde_yaml::Value>,
+        iter: &serde_json::Value,
+        index: usize,
+    ) -> Option<String> {
+        let cmd_body = self
+            .ctx
+            .render_template(cmd, &self.render_context(job, props, iter, index))
+            .unwrap();
+
         println!("Step => {}", cmd);
         if self.dry_mode {
-            return true;
+            return Some(String::new());
         }
 
-        let child = cmd_ctx.spawn();
+        let mut env = self.env.clone();
+        if let Some(job_env) = &job.env {
+            env.extend(job_env.clone());
+        }
 
-        return match child {
-            Ok(mut c) => match c.wait() {
-                Ok(code) => code.success(),
-                Err(_) => false,
-            },
-            Err(_) => false,
+        let backend = job.backend.clone().unwrap_or_default().build();
+        let shell = resolve_shell(&self.shell, &job.shell);
+        let timeout = job.timeout_secs.map(Duration::from_secs);
+        let retries = job.retries.unwrap_or(0);
+        let retry_delay_ms = job.retry_delay_ms.unwrap_or(0);
+
+        for attempt in 0..=retries {
+            let captured = backend.run_captured(&cmd_body, &env, job.dir.as_deref(), timeout, &shell);
+
+            if let Ok((result, stdout, _stderr)) = captured {
+                if result.success {
+                    return Some(stdout.trim().to_string());
+                }
+            }
+
+            if attempt < retries {
+                let backoff_ms = retry_delay_ms.saturating_mul(1u64 << attempt);
+                println!(
+                    "Step failed (attempt {}/{}), retrying in {backoff_ms}ms => {}",
+                    attempt + 1,
+                    retries + 1,
+                    cmd
+                );
+                std::thread::sleep(Duration::from_millis(backoff_ms));
+            }
+        }
+
+        None
+    }
+
+    /// Write a step's captured stdout/stderr to wherever its `StepLog` would
+    /// otherwise have sent it, so assertion-checked steps still produce the
+    /// configured log output instead of silently swallowing it
+    fn write_captured_output(
+        &self,
+        job: &Job,
+        props: &HashMap<String, serde_yaml::Value>,
+        iter: &serde_json::Value,
+        index: usize,
+        stdout: &str,
+        stderr: &str,
+    ) {
+        match resolve_log(&self.log, &job.log) {
+            StepLog::ToStdout(true) => {
+                print!("{stdout}");
+                eprint!("{stderr}");
+            }
+            StepLog::ToStdout(false) => {}
+            StepLog::Filepath(out_template) => {
+                let out_path = self.resolve_path(&out_template, job, props, iter, index);
+                if let Ok(mut out_file) = std::fs::OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(out_path)
+                {
+                    use std::io::Write;
+                    let _ = out_file.write_all(stdout.as_bytes());
+                    let _ = out_file.write_all(stderr.as_bytes());
+                }
+            }
+        }
+    }
+
+    /// Run a step and check its captured output and exit code against
+    /// `assertions`. Runs through the job's configured `backend`, same as
+    /// `run_step`, so a `Step::Assert` on a `ssh`/`docker` job still runs
+    /// remotely; the captured stdout/stderr are then forwarded to the job's
+    /// configured `StepLog` destination so the capture is transparent to the
+    /// rest of the pipeline. Retries up to `job.retries` times with
+    /// exponential backoff and kills the step if it runs past
+    /// `job.timeout_secs`, the same as `run_step`. Returns the last attempt's
+    /// output alongside the outcome, for `output_tail` in `--report`.
+    pub fn run_step_with_assertions(
+        &self,
+        cmd: &str,
+        job: &Job,
+        props: &HashMap<String, serde_yaml::Value>,
+        iter: &serde_json::Value,
+        index: usize,
+        assertions: &Assertions,
+    ) -> (AssertOutcome, String) {
+        let cmd_body = self
+            .ctx
+            .render_template(cmd, &self.render_context(job, props, iter, index))
+            .unwrap();
+
+        println!("Step => {}", cmd);
+        if self.dry_mode {
+            return (AssertOutcome::Passed, String::new());
+        }
+
+        let mut env = self.env.clone();
+        if let Some(job_env) = &job.env {
+            env.extend(job_env.clone());
+        }
+
+        let backend = job.backend.clone().unwrap_or_default().build();
+        let shell = resolve_shell(&self.shell, &job.shell);
+        let timeout = job.timeout_secs.map(Duration::from_secs);
+        let retries = job.retries.unwrap_or(0);
+        let retry_delay_ms = job.retry_delay_ms.unwrap_or(0);
+
+        let mut outcome = AssertOutcome::Failed(vec!["step did not run".to_string()]);
+        let mut tail = String::new();
+
+        for attempt in 0..=retries {
+            let captured = backend.run_captured(&cmd_body, &env, job.dir.as_deref(), timeout, &shell);
+
+            let (stdout, stderr, exit_code) = match captured {
+                Ok((result, stdout, stderr)) => (stdout, stderr, result.exit_code.unwrap_or(-1)),
+                Err(e) => {
+                    outcome = AssertOutcome::Failed(vec![format!("could not run command => {e}")]);
+                    tail = String::new();
+                    if attempt < retries {
+                        std::thread::sleep(Duration::from_millis(
+                            retry_delay_ms.saturating_mul(1u64 << attempt),
+                        ));
+                    }
+                    continue;
+                }
+            };
+
+            self.write_captured_output(job, props, iter, index, &stdout, &stderr);
+            let failures = check_assertions(assertions, &stdout, &stderr, exit_code);
+            tail = format!("{stdout}{stderr}");
+
+            if failures.is_empty() {
+                return (AssertOutcome::Passed, tail);
+            }
+
+            outcome = AssertOutcome::Failed(failures);
+            if attempt < retries {
+                let backoff_ms = retry_delay_ms.saturating_mul(1u64 << attempt);
+                println!(
+                    "Assertions failed (attempt {}/{}), retrying in {backoff_ms}ms => {}",
+                    attempt + 1,
+                    retries + 1,
+                    cmd
+                );
+                std::thread::sleep(Duration::from_millis(backoff_ms));
+            }
+        }
+
+        (outcome, tail)
+    }
+
+    /// Run every job's steps in assertion mode and return a pass/fail/skip summary.
+    /// A step is skipped when neither it nor its job declares any `assert` rules.
+    pub fn run_all_tests(&mut self) -> TestSummary {
+        let mut summary = TestSummary::default();
+
+        for job in self.jobs.clone().iter() {
+            let Some(steps) = &job.steps else {
+                continue;
+            };
+            let props = job.props.clone().unwrap_or_default();
+
+            for step in steps.iter() {
+                let (cmd, assertions) = match step {
+                    Step::Assert { cmd, assert } => (cmd, Some(assert.clone())),
+                    Step::Command(cmd) => (cmd, job.assert.clone()),
+                    Step::Register { cmd, .. } => (cmd, job.assert.clone()),
+                    Step::Job { .. } => continue,
+                };
+
+                match assertions {
+                    Some(assertions) => match self
+                        .run_step_with_assertions(
+                            cmd,
+                            job,
+                            &props,
+                            &serde_json::Value::Null,
+                            0,
+                            &assertions,
+                        )
+                        .0
+                    {
+                        AssertOutcome::Passed => {
+                            println!("PASS {} :: {}", job.name, cmd);
+                            summary.passed += 1;
+                        }
+                        AssertOutcome::Failed(reasons) => {
+                            println!("FAIL {} :: {}", job.name, cmd);
+                            for reason in reasons {
+                                println!("  {reason}");
+                            }
+                            summary.failed += 1;
+                        }
+                    },
+                    None => {
+                        println!("SKIP {} :: {} (no assertions)", job.name, cmd);
+                        summary.skipped += 1;
+                    }
+                }
+            }
+        }
+
+        summary
+    }
+
+    /// Whether every `Step::Job` dependency of `job` has already completed
+    fn depends_ready(&self, job: &Job) -> bool {
+        match &job.depends {
+            None => true,
+            Some(depends) => depends.iter().all(|depend| match depend {
+                Step::Job { job: name, .. } => self
+                    .get_job(name)
+                    .map(|dep| dep.completed.load(std::sync::atomic::Ordering::SeqCst))
+                    .unwrap_or(true),
+                Step::Command(_) | Step::Assert { .. } | Step::Register { .. } => true,
+            }),
+        }
+    }
+
+    /// Whether any `Step::Job` dependency of `job` failed (with `ignore_errors`
+    /// unset), meaning `job` should be skipped rather than run
+    fn depends_failed(&self, job: &Job) -> bool {
+        match &job.depends {
+            None => false,
+            Some(depends) => depends.iter().any(|depend| match depend {
+                Step::Job { job: name, .. } => self
+                    .get_job(name)
+                    .map(|dep| dep.failed.load(std::sync::atomic::Ordering::SeqCst))
+                    .unwrap_or(false),
+                Step::Command(_) | Step::Assert { .. } | Step::Register { .. } => false,
+            }),
+        }
+    }
+
+    /// Whether any `Step::Job` dependency of `job` actually executed its
+    /// steps this run (as opposed to being skipped by the incremental
+    /// cache), meaning `job`'s own cache hit can no longer be trusted
+    fn depends_ran(&self, job: &Job) -> bool {
+        match &job.depends {
+            None => false,
+            Some(depends) => depends.iter().any(|depend| match depend {
+                Step::Job { job: name, .. } => self
+                    .get_job(name)
+                    .map(|dep| dep.ran.load(std::sync::atomic::Ordering::SeqCst))
+                    .unwrap_or(false),
+                Step::Command(_) | Step::Assert { .. } | Step::Register { .. } => false,
+            }),
+        }
+    }
+
+    /// Whether every `output` path declared in `job.templates` still exists
+    /// on disk, so a cache hit isn't trusted once a generated file has been
+    /// deleted out from under it. An output containing an unresolved
+    /// template marker (e.g. `config.{{iter}}.yaml`) can't be checked
+    /// statically, since it resolves differently per iteration, so it's
+    /// treated as present.
+    fn declared_outputs_exist(&self, job: &Job) -> bool {
+        let Some(templates) = &job.templates else {
+            return true;
         };
+
+        templates.iter().all(|template| {
+            let Some((_input, output)) = template.split_once(":") else {
+                return true;
+            };
+            if output.contains("{{") {
+                return true;
+            }
+            Path::new(&self.config_dir).join(output).exists()
+        })
     }
 
-    /// Run all jobs sequentially
+    /// Run all jobs, scheduling them by dependency level: every job whose
+    /// `Step::Job` dependencies have already completed is handed to a fixed
+    /// pool of `max_parallel` workers (unbounded if 0) sharing a queue, so a
+    /// worker that finishes early immediately picks up the next ready job
+    /// instead of waiting on its slower level-mates. The next level only
+    /// starts once the current one has fully drained. A job whose
+    /// dependency failed is marked `Skipped` instead of running, and that
+    /// skip propagates to its own dependents in turn.
     pub fn run_all(&mut self) {
+        let mut remaining: Vec<String> = match topo_order(&self.jobs) {
+            Ok(order) => order,
+            Err(cycle) => {
+                println!("Dependency cycle detected among jobs => {:?}", cycle);
+                return;
+            }
+        };
+
+        while !remaining.is_empty() {
+            let mut still_remaining = Vec::with_capacity(remaining.len());
+            for name in remaining {
+                let job = self.get_job(&name).unwrap();
+                if self.depends_failed(job) {
+                    println!("Skipping '{}', a dependency failed", name);
+                    job.completed
+                        .store(true, std::sync::atomic::Ordering::SeqCst);
+                    job.failed.store(true, std::sync::atomic::Ordering::SeqCst);
+                    self.record_result(&name, JobState::Skipped, None, std::time::SystemTime::now(), None);
+                } else {
+                    still_remaining.push(name);
+                }
+            }
+
+            if still_remaining.is_empty() {
+                break;
+            }
+
+            let (ready, not_ready): (Vec<String>, Vec<String>) = still_remaining
+                .into_iter()
+                .partition(|name| self.depends_ready(self.get_job(name).unwrap()));
+
+            if ready.is_empty() {
+                println!("No job is ready to run, but jobs remain => {:?}", not_ready);
+                break;
+            }
+
+            let num_workers = if self.max_parallel == 0 {
+                ready.len().max(1)
+            } else {
+                self.max_parallel
+            };
+            let queue = std::sync::Mutex::new(VecDeque::from(ready));
+            std::thread::scope(|scope| {
+                for _ in 0..num_workers {
+                    scope.spawn(|| loop {
+                        let name = queue.lock().unwrap().pop_front();
+                        let Some(name) = name else { break };
+                        self.run_job(self.get_job(&name).unwrap());
+                    });
+                }
+            });
+
+            remaining = not_ready;
+        }
+    }
+
+    /// Expand a job's own `watch` globs (relative to the config directory)
+    /// into concrete paths
+    fn job_watch_globs(&self, job: &Job) -> Vec<PathBuf> {
+        let Some(patterns) = &job.watch else {
+            return Vec::new();
+        };
+
+        let mut paths = Vec::new();
+        for pattern in patterns.iter() {
+            let full_pattern = Path::new(&self.config_dir).join(pattern);
+            let Some(full_pattern) = full_pattern.to_str() else {
+                continue;
+            };
+            match glob::glob(full_pattern) {
+                Ok(entries) => paths.extend(entries.flatten()),
+                Err(e) => println!("Invalid watch pattern '{}' => {e}", pattern),
+            }
+        }
+        paths
+    }
+
+    /// Every path this runner should watch in `--watch` mode: the config
+    /// file itself, every template input path, every job's `dir`, and every
+    /// job's own `watch` globs
+    fn watch_paths(&self, config_path: &str) -> Vec<PathBuf> {
+        let mut paths = vec![PathBuf::from(config_path)];
         for job in self.jobs.iter() {
-            self.run_job(job);
+            if let Some(templates) = &job.templates {
+                for template in templates.iter() {
+                    if let Some((input, _output)) = template.split_once(":") {
+                        paths.push(Path::new(&self.config_dir).join(input));
+                    }
+                }
+            }
+            if let Some(dir) = &job.dir {
+                paths.push(Path::new(&self.config_dir).join(dir));
+            }
+            paths.extend(self.job_watch_globs(job));
+        }
+        paths
+    }
+
+    /// Job names whose template input, `dir`, or `watch` glob was touched by
+    /// one of `events`, plus their transitive dependents (via `Step::Job`
+    /// edges in `depends`)
+    fn jobs_for_changed_paths(&self, events: &[notify::Result<notify::Event>]) -> HashSet<String> {
+        let mut changed: HashSet<String> = HashSet::new();
+        for event in events.iter().flatten() {
+            for path in &event.paths {
+                for job in self.jobs.iter() {
+                    let touches_template = job.templates.as_ref().is_some_and(|templates| {
+                        templates.iter().any(|template| {
+                            template
+                                .split_once(":")
+                                .map(|(input, _output)| {
+                                    Path::new(&self.config_dir).join(input) == *path
+                                })
+                                .unwrap_or(false)
+                        })
+                    });
+                    let touches_watch = self.job_watch_globs(job).iter().any(|p| p == path);
+                    let touches_dir = job
+                        .dir
+                        .as_ref()
+                        .is_some_and(|dir| path.starts_with(Path::new(&self.config_dir).join(dir)));
+
+                    if touches_template || touches_watch || touches_dir {
+                        changed.insert(job.name.clone());
+                    }
+                }
+            }
+        }
+
+        let mut affected = changed.clone();
+        let mut added = true;
+        while added {
+            added = false;
+            for job in self.jobs.iter() {
+                if affected.contains(&job.name) {
+                    continue;
+                }
+                let depends_on_affected = job.depends.as_ref().is_some_and(|depends| {
+                    depends.iter().any(
+                        |depend| matches!(depend, Step::Job { job: name, .. } if affected.contains(name)),
+                    )
+                });
+                if depends_on_affected {
+                    affected.insert(job.name.clone());
+                    added = true;
+                }
+            }
+        }
+
+        affected
+    }
+
+    /// Register every path with `watcher`, watching directories recursively
+    /// and individual files directly
+    fn register_watches(watcher: &mut notify::RecommendedWatcher, paths: Vec<PathBuf>) {
+        for path in paths {
+            let mode = if path.is_dir() {
+                RecursiveMode::Recursive
+            } else {
+                RecursiveMode::NonRecursive
+            };
+            if let Err(e) = watcher.watch(&path, mode) {
+                println!("Could not watch '{}' => {e}", path.display());
+            }
+        }
+    }
+
+    /// Run once, then keep watching the config file and template inputs,
+    /// re-running only the jobs whose inputs changed (plus their dependents)
+    /// until interrupted. A change to the config file itself reloads and
+    /// re-validates it before the next run.
+    pub fn run_watch(&mut self, config_path: &str) {
+        self.run_all();
+        self.save_state();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut watcher = match notify::recommended_watcher(tx) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                println!("Could not start the file watcher => {e}");
+                return;
+            }
+        };
+
+        let paths = self.watch_paths(config_path);
+        println!("watching {} paths", paths.len());
+        Self::register_watches(&mut watcher, paths);
+
+        loop {
+            println!("waiting for changes…");
+            let Ok(first_event) = rx.recv() else {
+                break;
+            };
+
+            // Coalesce any further events over a short debounce window
+            let mut events = vec![first_event];
+            while let Ok(event) = rx.recv_timeout(Duration::from_millis(300)) {
+                events.push(event);
+            }
+
+            let config_path_buf = PathBuf::from(config_path);
+            let config_changed = events.iter().flatten().any(|event| {
+                event
+                    .paths
+                    .iter()
+                    .any(|path| path == &config_path_buf)
+            });
+
+            if config_changed {
+                println!("Config file changed, reloading…");
+                let config = match Config::from_file(config_path) {
+                    Ok(config) => config,
+                    Err(e) => {
+                        println!("Could not reload configuration => {e}");
+                        continue;
+                    }
+                };
+                if !config.check() {
+                    println!("Errors in the reloaded configuration.");
+                    continue;
+                }
+                let (dry_mode, force, max_parallel) = (self.dry_mode, self.force, self.max_parallel);
+                *self = Runner::new(&config, &self.config_dir);
+                self.dry_mode = dry_mode;
+                self.force = force;
+                self.max_parallel = max_parallel;
+                let paths = self.watch_paths(config_path);
+                println!("watching {} paths", paths.len());
+                Self::register_watches(&mut watcher, paths);
+                self.run_all();
+                self.save_state();
+                println!("cycle complete: reloaded config and re-ran every job");
+                continue;
+            }
+
+            let affected = self.jobs_for_changed_paths(&events);
+            if affected.is_empty() {
+                continue;
+            }
+            for name in &affected {
+                if let Some(job) = self.get_job(name) {
+                    job.completed
+                        .store(false, std::sync::atomic::Ordering::SeqCst);
+                    job.failed.store(false, std::sync::atomic::Ordering::SeqCst);
+                }
+            }
+            // Unaffected jobs stay `completed`, so bypassing the incremental
+            // cache here only re-runs the affected subset
+            let force = self.force;
+            self.force = true;
+            self.run_all();
+            self.force = force;
+            self.save_state();
+            println!("cycle complete: re-ran {} job(s) => {:?}", affected.len(), affected);
         }
     }
 
@@ -169,73 +998,304 @@ impl<'a> Runner<'static> {
     /// Otherwise, return true if the job has not ben run
     pub fn job_should_run(&self, job: &Job) -> bool {
         if let Some(conds) = &job.when {
-            let codes: Vec<bool> = conds.iter().map(|cmd| self.run_step(cmd, &job)).collect();
+            let props = job.props.clone().unwrap_or_default();
+            let codes: Vec<bool> = conds
+                .iter()
+                .map(|cmd| self.run_step(cmd, &job, &props, &serde_json::Value::Null, 0))
+                .collect();
             return codes.iter().all(|&c| c == true);
         }
-        return !job.completed.get();
+        return !job.completed.load(std::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Run a `Step::Assert` for real (not just `--test`), printing a
+    /// diff-style report of expected vs actual on mismatch. Returns whether
+    /// it passed alongside the captured output, for `output_tail` in `--report`.
+    fn run_step_assert(
+        &self,
+        cmd: &str,
+        job: &Job,
+        props: &HashMap<String, serde_yaml::Value>,
+        iter: &serde_json::Value,
+        index: usize,
+        assert: &Assertions,
+    ) -> (bool, Option<String>) {
+        let (outcome, tail) = self.run_step_with_assertions(cmd, job, props, iter, index, assert);
+        match outcome {
+            AssertOutcome::Passed => (true, Some(tail)),
+            AssertOutcome::Failed(reasons) => {
+                println!("Assertions failed for '{}' :: {cmd}", job.name);
+                for reason in reasons {
+                    println!("  {reason}");
+                }
+                (false, Some(tail))
+            }
+        }
+    }
+
+    /// Dispatch a single `Step` (a `depends` entry or a regular step) and
+    /// return whether it succeeded, alongside any output it captured (for
+    /// `output_tail` in `--report`), registering captured output into
+    /// `props`. A plain `Step::Command` always reports `None` here: it
+    /// streams straight to its `StepLog` destination via `run_step` rather
+    /// than being captured, so there's nothing to surface as a tail.
+    fn run_one_step(
+        &self,
+        step: &Step,
+        job: &Job,
+        props: &mut HashMap<String, serde_yaml::Value>,
+        iter: &serde_json::Value,
+        index: usize,
+    ) -> (bool, Option<String>) {
+        match step {
+            Step::Command(cmd) => (self.run_step(cmd, job, props, iter, index), None),
+            Step::Assert { cmd, assert } => self.run_step_assert(cmd, job, props, iter, index, assert),
+            Step::Register { cmd, register } => {
+                match self.run_step_capture(cmd, job, props, iter, index) {
+                    Some(output) => {
+                        props.insert(register.clone(), serde_yaml::Value::String(output.clone()));
+                        (true, Some(output))
+                    }
+                    None => (false, None),
+                }
+            }
+            Step::Job { job: jobname, .. } => (self.run_job(self.get_job(jobname).unwrap()), None),
+        }
     }
 
     ///
     pub fn run_job(&self, job: &Job) -> bool {
+        let started_at = std::time::SystemTime::now();
         println!("Loading job => {}", job.name);
         if !self.job_should_run(&job) {
             println!("Job has already been executed");
+            self.record_result(&job.name, JobState::Skipped, None, started_at, None);
             return true;
         }
 
+        let mut props = job.props.clone().unwrap_or_default();
+
         if let Some(depends) = &job.depends {
             for depend in depends.iter() {
-                match depend {
-                    Step::Command(cmd) => self.run_step(&cmd, &job),
-                    Step::Job {
-                        job: jobname,
-                        props: _,
-                    } => self.run_job(self.get_job(jobname).unwrap()),
-                };
+                self.run_one_step(depend, &job, &mut props, &serde_json::Value::Null, 0);
             }
         }
 
+        // Checked after `depends` runs (rather than before) so `depends_ran`
+        // reflects this invocation's own dependency run, not just whatever
+        // happened to run earlier in the process — `monaco --job <name>`
+        // calls `run_job` directly without going through `run_all`'s
+        // scheduler, so a dependency's `ran` flag would otherwise never be
+        // set before this check runs.
+        let fingerprint = fingerprint_job(job, &self.config_dir);
+        if !self.force
+            && !self.depends_ran(job)
+            && self.declared_outputs_exist(job)
+            && self.state.lock().unwrap().is_unchanged(job, &fingerprint)
+        {
+            println!("Job '{}' is unchanged, skipping (use --force to re-run)", job.name);
+            job.completed
+                .store(true, std::sync::atomic::Ordering::SeqCst);
+            self.record_result(&job.name, JobState::Skipped, None, started_at, None);
+            return true;
+        }
+
         if job.steps.is_none() {
-            job.completed.set(true);
+            job.completed.store(true, std::sync::atomic::Ordering::SeqCst);
+            job.ran.store(true, std::sync::atomic::Ordering::SeqCst);
+            self.state.lock().unwrap().update(job, fingerprint);
+            self.record_result(&job.name, JobState::Succeeded, None, started_at, None);
             return true;
         }
 
-        if let Some(iters) = &job.iters {
-            match iters {
-                Iter::Inf(_) => println!("Job Inf"),
-                Iter::Values(lst) => println!("Job iterations: {lst:?}"),
-                Iter::Range { from, to, by } => {
-                    println!("Job range: {:?}, {:?}, {:?}", from, to, by)
+        let job_steps = &job.steps.clone().unwrap();
+
+        if job.is_looping() {
+            println!("Job loops until a step fails");
+            let mut index = 0usize;
+            loop {
+                let iter_value = serde_json::json!(index);
+                self.render_templates(&job, &props, &iter_value, index);
+                let mut pass_failed = false;
+                for step in job_steps.iter() {
+                    let (ok, _tail) = self.run_one_step(step, &job, &mut props, &iter_value, index);
+                    if !ok {
+                        pass_failed = true;
+                        break;
+                    }
                 }
+                if pass_failed {
+                    break;
+                }
+                index += 1;
             }
+            job.completed.store(true, std::sync::atomic::Ordering::SeqCst);
+            job.ran.store(true, std::sync::atomic::Ordering::SeqCst);
+            self.state.lock().unwrap().update(job, fingerprint);
+            self.record_result(&job.name, JobState::Succeeded, Some(0), started_at, None);
+            return true;
+        }
+
+        let iterations = job.resolve_iters();
+        let passes: Vec<serde_json::Value> = if iterations.is_empty() {
+            vec![serde_json::Value::Null]
+        } else {
+            iterations
         };
 
-        // TODO: Executing the templates
-        if let Some(templates) = &job.templates {
-            for template in templates.iter() {
-                let paths: Vec<&str> = template.split(":").collect();
-                println!("Input {} => {}", paths[0], paths[1]);
+        for (index, iter_value) in passes.iter().enumerate() {
+            self.render_templates(&job, &props, iter_value, index);
+            for step in job_steps.iter() {
+                let (exit_ok, tail) = self.run_one_step(step, &job, &mut props, iter_value, index);
+
+                if job.ignore_errors == false && exit_ok == false {
+                    println!("Step executed with errors");
+                    job.completed.store(true, std::sync::atomic::Ordering::SeqCst);
+                    job.failed.store(true, std::sync::atomic::Ordering::SeqCst);
+                    self.record_result(&job.name, JobState::Failed, Some(1), started_at, tail);
+                    return exit_ok;
+                };
             }
         }
 
-        let job_steps = &job.steps.clone().unwrap();
-        for step in job_steps.iter() {
-            let exit_ok: bool = match step {
-                Step::Command(cmd) => self.run_step(&cmd, &job),
-                Step::Job {
-                    job: jobname,
-                    props: _,
-                } => self.run_job(self.get_job(jobname).unwrap()),
-            };
+        job.completed.store(true, std::sync::atomic::Ordering::SeqCst);
+        job.ran.store(true, std::sync::atomic::Ordering::SeqCst);
+        self.state.lock().unwrap().update(job, fingerprint);
+        self.record_result(&job.name, JobState::Succeeded, Some(0), started_at, None);
+        return true;
+    }
+}
 
-            if job.ignore_errors == false && exit_ok == false {
-                println!("Step executed with errors");
-                job.completed.set(true);
-                return exit_ok;
-            };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// A bare job with every optional field unset, for tests to fill in
+    /// whichever fields the case under test actually cares about.
+    fn test_job(name: &str) -> Job {
+        Job {
+            name: name.to_string(),
+            dir: None,
+            env: None,
+            props: None,
+            secrets: None,
+            steps: None,
+            iters: None,
+            depends: None,
+            when: None,
+            assert: None,
+            templates: None,
+            ignore_errors: false,
+            log: None,
+            backend: None,
+            shell: None,
+            retries: None,
+            retry_delay_ms: None,
+            timeout_secs: None,
+            watch: None,
+            completed: AtomicBool::new(false),
+            failed: AtomicBool::new(false),
+            ran: AtomicBool::new(false),
         }
+    }
 
-        job.completed.set(true);
-        return true;
+    fn depend_on(name: &str) -> Step {
+        Step::Job {
+            job: name.to_string(),
+            props: None,
+        }
+    }
+
+    fn test_runner(jobs: Vec<Job>) -> Runner<'static> {
+        Runner {
+            jobs,
+            env: HashMap::new(),
+            props: HashMap::new(),
+            dry_mode: false,
+            ctx: Handlebars::new(),
+            log: StepLog::ToStdout(false),
+            shell: default_shell(),
+            config_dir: ".".to_string(),
+            force: false,
+            max_parallel: 0,
+            state: std::sync::Mutex::new(State::default()),
+            results: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn depends_failed_true_when_dependency_failed() {
+        let a = test_job("a");
+        a.failed.store(true, Ordering::SeqCst);
+        let mut b = test_job("b");
+        b.depends = Some(vec![depend_on("a")]);
+
+        let runner = test_runner(vec![a, b]);
+        assert!(runner.depends_failed(runner.get_job("b").unwrap()));
+    }
+
+    #[test]
+    fn depends_failed_false_when_dependency_ok() {
+        let a = test_job("a");
+        let mut b = test_job("b");
+        b.depends = Some(vec![depend_on("a")]);
+
+        let runner = test_runner(vec![a, b]);
+        assert!(!runner.depends_failed(runner.get_job("b").unwrap()));
+    }
+
+    #[test]
+    fn depends_failed_cascades_transitively_through_run_all() {
+        // a fails outright; b depends on a; c depends on b. The skip should
+        // cascade down the whole chain instead of stopping at the direct
+        // dependent, matching `run_all`'s doc comment.
+        let mut a = test_job("a");
+        a.steps = Some(vec![Step::Command("exit 1".to_string())]);
+
+        let mut b = test_job("b");
+        b.depends = Some(vec![depend_on("a")]);
+
+        let mut c = test_job("c");
+        c.depends = Some(vec![depend_on("b")]);
+
+        let mut runner = test_runner(vec![a, b, c]);
+        runner.run_all();
+
+        assert!(runner.get_job("a").unwrap().failed.load(Ordering::SeqCst));
+        assert!(runner.get_job("b").unwrap().failed.load(Ordering::SeqCst));
+        assert!(runner.get_job("c").unwrap().failed.load(Ordering::SeqCst));
+
+        let results = runner.results();
+        let state_of = |name: &str| results.iter().find(|r| r.name == name).unwrap().state;
+        assert_eq!(state_of("b"), JobState::Skipped);
+        assert_eq!(state_of("c"), JobState::Skipped);
+    }
+
+    #[test]
+    fn depends_ran_makes_a_stale_cache_hit_rerun() {
+        // "main" depends on "dep". Its own fingerprint is pre-recorded as
+        // unchanged, so without consulting `depends_ran` it would skip via
+        // the incremental cache — even though `dep` just ran in this same
+        // call and may have produced new state `main` should react to.
+        let dep = test_job("dep");
+        let mut main = test_job("main");
+        main.depends = Some(vec![depend_on("dep")]);
+
+        let runner = test_runner(vec![dep, main]);
+        let fingerprint = fingerprint_job(runner.get_job("main").unwrap(), &runner.config_dir);
+        runner
+            .state
+            .lock()
+            .unwrap()
+            .update(runner.get_job("main").unwrap(), fingerprint);
+
+        runner.run_job(runner.get_job("main").unwrap());
+
+        assert!(runner.get_job("dep").unwrap().ran.load(Ordering::SeqCst));
+
+        let results = runner.results();
+        let main_result = results.iter().find(|r| r.name == "main").unwrap();
+        assert_eq!(main_result.state, JobState::Succeeded);
     }
 }
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::job::Job;
+
+/// Persisted fingerprints of every job, used to skip jobs whose definition
+/// and template inputs have not changed since the last successful run.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    pub fingerprints: HashMap<String, String>,
+}
+
+impl State {
+    /// Path to the state file next to the config, `.monaco-state.json`
+    pub fn path(config_dir: &str) -> PathBuf {
+        Path::new(config_dir).join(".monaco-state.json")
+    }
+
+    /// Load the persisted state, or a fresh empty one if it doesn't exist
+    /// or can't be parsed
+    pub fn load(config_dir: &str) -> Self {
+        match fs::read_to_string(Self::path(config_dir)) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+            Err(_) => State::default(),
+        }
+    }
+
+    /// Persist the state to the config directory
+    pub fn save(&self, config_dir: &str) {
+        if let Ok(content) = serde_json::to_string_pretty(self) {
+            let _ = fs::write(Self::path(config_dir), content);
+        }
+    }
+
+    /// Whether `job`'s stored fingerprint matches the one just computed
+    pub fn is_unchanged(&self, job: &Job, fingerprint: &str) -> bool {
+        self.fingerprints.get(&job.name).map(String::as_str) == Some(fingerprint)
+    }
+
+    /// Record `job`'s latest fingerprint after a successful run
+    pub fn update(&mut self, job: &Job, fingerprint: String) {
+        self.fingerprints.insert(job.name.clone(), fingerprint);
+    }
+}
+
+/// Compute a deterministic fingerprint for a job: its canonical JSON
+/// definition (command templates, resolved `props`, `env`, `iters`) plus the
+/// contents of every template input path, so a job only re-runs when
+/// something it actually depends on changes.
+pub fn fingerprint_job(job: &Job, config_dir: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(canonical_json(job).as_bytes());
+
+    if let Some(templates) = &job.templates {
+        for template in templates.iter() {
+            if let Some((input, _output)) = template.split_once(":") {
+                let input_path = Path::new(config_dir).join(input);
+                match fs::read(&input_path) {
+                    Ok(bytes) => hasher.update(&bytes),
+                    Err(_) => hasher.update(input.as_bytes()),
+                }
+            }
+        }
+    }
+
+    format!("{:x}", hasher.finalize())
+}
+
+/// Serialize a job into a canonical JSON string: map keys are sorted so
+/// semantically equal jobs hash equal regardless of serde ordering
+fn canonical_json(job: &Job) -> String {
+    let value = serde_json::to_value(job).unwrap_or(serde_json::Value::Null);
+    canonicalize(&value).to_string()
+}
+
+fn canonicalize(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let mut sorted = serde_json::Map::new();
+            for key in keys {
+                sorted.insert(key.clone(), canonicalize(&map[key]));
+            }
+            serde_json::Value::Object(sorted)
+        }
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.iter().map(canonicalize).collect())
+        }
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    fn test_job(name: &str) -> Job {
+        Job {
+            name: name.to_string(),
+            dir: None,
+            env: None,
+            props: None,
+            secrets: None,
+            steps: None,
+            iters: None,
+            depends: None,
+            when: None,
+            assert: None,
+            templates: None,
+            ignore_errors: false,
+            log: None,
+            backend: None,
+            shell: None,
+            retries: None,
+            retry_delay_ms: None,
+            timeout_secs: None,
+            watch: None,
+            completed: AtomicBool::new(false),
+            failed: AtomicBool::new(false),
+            ran: AtomicBool::new(false),
+        }
+    }
+
+    #[test]
+    fn fingerprint_is_stable_for_an_unchanged_job() {
+        let job = test_job("a");
+        assert_eq!(fingerprint_job(&job, "."), fingerprint_job(&job, "."));
+    }
+
+    #[test]
+    fn fingerprint_changes_with_the_job_definition() {
+        let mut a = test_job("a");
+        a.env = Some(HashMap::from([("FOO".to_string(), "bar".to_string())]));
+        let b = test_job("a");
+        assert_ne!(fingerprint_job(&a, "."), fingerprint_job(&b, "."));
+    }
+
+    #[test]
+    fn fingerprint_ignores_map_key_order() {
+        let mut a = test_job("a");
+        a.env = Some(HashMap::from([
+            ("FOO".to_string(), "1".to_string()),
+            ("BAR".to_string(), "2".to_string()),
+        ]));
+        let mut b = test_job("a");
+        b.env = Some(HashMap::from([
+            ("BAR".to_string(), "2".to_string()),
+            ("FOO".to_string(), "1".to_string()),
+        ]));
+        assert_eq!(fingerprint_job(&a, "."), fingerprint_job(&b, "."));
+    }
+
+    #[test]
+    fn fingerprint_changes_when_a_template_input_changes() {
+        let dir = std::env::temp_dir().join(format!("monaco-state-test-{:?}", std::thread::current().id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input_path = dir.join("input.tmpl");
+
+        let mut job = test_job("a");
+        job.templates = Some(vec!["input.tmpl:output.txt".to_string()]);
+
+        fs::write(&input_path, "v1").unwrap();
+        let before = fingerprint_job(&job, dir.to_str().unwrap());
+
+        fs::write(&input_path, "v2").unwrap();
+        let after = fingerprint_job(&job, dir.to_str().unwrap());
+
+        assert_ne!(before, after);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn state_is_unchanged_tracks_the_last_recorded_fingerprint() {
+        let job = test_job("a");
+        let mut state = State::default();
+        let fingerprint = fingerprint_job(&job, ".");
+
+        assert!(!state.is_unchanged(&job, &fingerprint));
+        state.update(&job, fingerprint.clone());
+        assert!(state.is_unchanged(&job, &fingerprint));
+        assert!(!state.is_unchanged(&job, "some-other-fingerprint"));
+    }
+}